@@ -0,0 +1,126 @@
+use std::ffi::CString;
+use std::time::{Duration, Instant};
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_backup, sqlite3_backup_finish, sqlite3_backup_init, sqlite3_backup_pagecount,
+    sqlite3_backup_remaining, sqlite3_backup_step, SQLITE_BUSY, SQLITE_DONE, SQLITE_LOCKED, SQLITE_OK,
+};
+
+use crate::error::Error;
+
+/// Progress reported after each [`sqlite3_backup_step`] call that returns `SQLITE_OK`.
+#[derive(Debug, Clone, Copy)]
+pub struct BackupProgress {
+    /// Pages still to be copied.
+    pub remaining: i32,
+    /// Total number of pages in the source database as of the last step.
+    pub pagecount: i32,
+}
+
+/// Copy `page_count` pages at a time from `src` to `dest`, retrying on `SQLITE_BUSY`/
+/// `SQLITE_LOCKED` until `busy_timeout` elapses, calling `on_progress` after each step that
+/// returns `SQLITE_OK`. The final step, which returns `SQLITE_DONE`, reports no progress.
+///
+/// Runs entirely on the caller's thread; on the SQLite backend this is always the worker thread
+/// that owns the connection's `sqlite3*`, so this is safe to call directly with the raw handles.
+pub(crate) async fn backup<F>(
+    src: *mut sqlite3,
+    src_name: &str,
+    dest: *mut sqlite3,
+    dest_name: &str,
+    page_count: i32,
+    busy_timeout: Duration,
+    mut on_progress: F,
+) -> Result<(), Error>
+where
+    F: FnMut(BackupProgress),
+{
+    let src_name = CString::new(src_name).map_err(|e| Error::Configuration(e.to_string().into()))?;
+    let dest_name =
+        CString::new(dest_name).map_err(|e| Error::Configuration(e.to_string().into()))?;
+
+    let handle = unsafe { sqlite3_backup_init(dest, dest_name.as_ptr(), src, src_name.as_ptr()) };
+
+    if handle.is_null() {
+        return Err(Error::Configuration("sqlite3_backup_init() failed".into()));
+    }
+
+    let result = run_steps(handle, page_count, busy_timeout, &mut on_progress).await;
+
+    // `sqlite3_backup_finish()` returns the backup's last error, which is more specific than
+    // the retry loop's timeout error, so prefer it when the loop didn't already fail.
+    let finish_ret = unsafe { sqlite3_backup_finish(handle) };
+
+    result.and_then(|()| {
+        if finish_ret != SQLITE_OK {
+            Err(Error::Configuration(
+                format!("sqlite3_backup_finish() failed: {finish_ret}").into(),
+            ))
+        } else {
+            Ok(())
+        }
+    })
+}
+
+async fn run_steps<F>(
+    handle: *mut sqlite3_backup,
+    page_count: i32,
+    busy_timeout: Duration,
+    on_progress: &mut F,
+) -> Result<(), Error>
+where
+    F: FnMut(BackupProgress),
+{
+    let started_at = Instant::now();
+
+    loop {
+        let ret = unsafe { sqlite3_backup_step(handle, page_count) };
+
+        match ret {
+            SQLITE_DONE => return Ok(()),
+            SQLITE_OK => {
+                let remaining = unsafe { sqlite3_backup_remaining(handle) };
+                let pagecount = unsafe { sqlite3_backup_pagecount(handle) };
+                on_progress(BackupProgress { remaining, pagecount });
+            }
+            SQLITE_BUSY | SQLITE_LOCKED => {
+                if started_at.elapsed() >= busy_timeout {
+                    return Err(Error::Configuration(
+                        format!("backup timed out waiting on a lock (sqlite3_backup_step() returned {ret})").into(),
+                    ));
+                }
+                // `sqlite3_backup_step` has no async-friendly wait primitive of its own; a short
+                // yield gives the other connection a chance to release the lock.
+                yield_now().await;
+            }
+            _ => {
+                return Err(Error::Configuration(
+                    format!("sqlite3_backup_step() failed: {ret}").into(),
+                ));
+            }
+        }
+    }
+}
+
+async fn yield_now() {
+    struct YieldNow(bool);
+
+    impl std::future::Future for YieldNow {
+        type Output = ();
+
+        fn poll(
+            mut self: std::pin::Pin<&mut Self>,
+            cx: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<()> {
+            if self.0 {
+                std::task::Poll::Ready(())
+            } else {
+                self.0 = true;
+                cx.waker().wake_by_ref();
+                std::task::Poll::Pending
+            }
+        }
+    }
+
+    YieldNow(false).await
+}
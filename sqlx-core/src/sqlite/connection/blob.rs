@@ -0,0 +1,346 @@
+use std::ffi::CString;
+use std::future::Future;
+use std::io;
+use std::os::raw::c_int;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures_channel::oneshot;
+use futures_io::{AsyncRead, AsyncSeek, AsyncWrite};
+use libsqlite3_sys::{
+    sqlite3, sqlite3_blob, sqlite3_blob_bytes, sqlite3_blob_close, sqlite3_blob_open,
+    sqlite3_blob_read, sqlite3_blob_reopen, sqlite3_blob_write, SQLITE_OK,
+};
+
+use crate::error::Error;
+
+type BoxFuture<T> = Pin<Box<dyn Future<Output = T> + Send>>;
+
+/// Runs a unit of work against a connection's raw `sqlite3*` on the single thread that owns it.
+///
+/// SQLite connections opened `OPEN_NOMUTEX` (the crate's default, see
+/// [`SqliteConnectOptions::serialized`](crate::sqlite::SqliteConnectOptions::serialized)) are
+/// not safe to touch from more than one thread at a time. [`SqliteBlob`] holds a handle to this
+/// instead of running `sqlite3_blob_*` calls directly wherever it happens to be polled, so BLOB
+/// I/O can't race with ordinary query execution on the same connection.
+pub(crate) trait ConnectionWorker: Send + Sync {
+    /// Run `job` on the thread that owns the connection.
+    fn dispatch(&self, job: Box<dyn FnOnce() + Send>);
+}
+
+/// A handle to a single BLOB value, opened via
+/// [`sqlite3_blob_open()`](https://www.sqlite.org/c3ref/blob_open.html), allowing random and
+/// streaming access without loading the whole value into memory.
+///
+/// SQLite requires a BLOB's length to stay fixed while the handle points at it: it must be
+/// pre-sized (e.g. with `zeroblob()`) before opening, and [`write_at`][Self::write_at] refuses
+/// writes that would extend past the current length.
+pub struct SqliteBlob {
+    handle: *mut sqlite3_blob,
+    size: i32,
+    pos: i64,
+    worker: Arc<dyn ConnectionWorker>,
+    pending_read: Option<BoxFuture<Result<Vec<u8>, Error>>>,
+    pending_write: Option<(usize, BoxFuture<Result<(), Error>>)>,
+}
+
+// The blob handle is never touched outside of a job run by `worker`, on the thread that owns
+// the connection.
+unsafe impl Send for SqliteBlob {}
+
+impl SqliteBlob {
+    /// Open the BLOB stored in `table.column` at the given `rowid`.
+    ///
+    /// `db_name` is usually `"main"`. Pass `read_write = true` to open for writing. `worker`
+    /// dispatches subsequent reads/writes/reopens onto the thread that owns `conn`.
+    pub(crate) fn open(
+        conn: *mut sqlite3,
+        worker: Arc<dyn ConnectionWorker>,
+        db_name: &str,
+        table: &str,
+        column: &str,
+        rowid: i64,
+        read_write: bool,
+    ) -> Result<Self, Error> {
+        let db_name = c_string(db_name)?;
+        let table = c_string(table)?;
+        let column = c_string(column)?;
+
+        let mut handle: *mut sqlite3_blob = std::ptr::null_mut();
+
+        let ret = unsafe {
+            sqlite3_blob_open(
+                conn,
+                db_name.as_ptr(),
+                table.as_ptr(),
+                column.as_ptr(),
+                rowid,
+                read_write as c_int,
+                &mut handle,
+            )
+        };
+
+        if ret != SQLITE_OK {
+            return Err(Error::Configuration(
+                format!("sqlite3_blob_open() failed: {ret}").into(),
+            ));
+        }
+
+        let size = unsafe { sqlite3_blob_bytes(handle) };
+
+        Ok(SqliteBlob {
+            handle,
+            size,
+            pos: 0,
+            worker,
+            pending_read: None,
+            pending_write: None,
+        })
+    }
+
+    /// The length of the BLOB in bytes. Writes cannot change it; [`reopen`][Self::reopen]
+    /// updates it to the length of the newly opened row's BLOB.
+    pub fn blob_size(&self) -> i32 {
+        self.size
+    }
+
+    /// Read `len` bytes starting at byte offset `offset`, dispatched onto the connection's
+    /// worker thread (see [`ConnectionWorker`]).
+    pub async fn read_at(&self, offset: i32, len: usize) -> Result<Vec<u8>, Error> {
+        self.dispatch_read(offset, len).await
+    }
+
+    /// Write `buf` starting at byte offset `offset`, dispatched onto the connection's worker
+    /// thread (see [`ConnectionWorker`]).
+    ///
+    /// Returns an error if `offset + buf.len()` would exceed [`blob_size`][Self::blob_size];
+    /// SQLite cannot grow a BLOB in place.
+    pub async fn write_at(&self, offset: i32, buf: &[u8]) -> Result<(), Error> {
+        self.check_write_bounds(offset, buf.len())?;
+        self.dispatch_write(offset, buf.to_vec()).await
+    }
+
+    /// Move this handle to point at the BLOB in the same `table.column` but a different
+    /// `rowid`, without the overhead of closing and reopening. Dispatched onto the connection's
+    /// worker thread (see [`ConnectionWorker`]).
+    pub async fn reopen(&mut self, rowid: i64) -> Result<(), Error> {
+        let handle = Handle(self.handle);
+        let (tx, rx) = oneshot::channel();
+
+        self.worker.dispatch(Box::new(move || {
+            let handle = handle;
+            let ret = unsafe { sqlite3_blob_reopen(handle.0, rowid) };
+            let result = if ret != SQLITE_OK {
+                Err(Error::Configuration(
+                    format!("sqlite3_blob_reopen() failed: {ret}").into(),
+                ))
+            } else {
+                Ok(unsafe { sqlite3_blob_bytes(handle.0) })
+            };
+            let _ = tx.send(result);
+        }));
+
+        let size = recv(rx).await?;
+        self.size = size;
+        self.pos = 0;
+
+        Ok(())
+    }
+
+    fn check_write_bounds(&self, offset: i32, len: usize) -> Result<(), Error> {
+        let end = offset.checked_add(len as i32);
+        if end.map_or(true, |end| end > self.size) {
+            return Err(Error::Configuration(
+                format!(
+                    "write of {len} bytes at offset {offset} would exceed the blob's fixed size \
+                     of {}; pre-size it with zeroblob() first",
+                    self.size
+                )
+                .into(),
+            ));
+        }
+
+        Ok(())
+    }
+
+    fn dispatch_read(&self, offset: i32, len: usize) -> BoxFuture<Result<Vec<u8>, Error>> {
+        let handle = Handle(self.handle);
+        let (tx, rx) = oneshot::channel();
+
+        self.worker.dispatch(Box::new(move || {
+            let handle = handle;
+            let mut buf = vec![0u8; len];
+            let ret = unsafe {
+                sqlite3_blob_read(
+                    handle.0,
+                    buf.as_mut_ptr() as *mut _,
+                    buf.len() as c_int,
+                    offset,
+                )
+            };
+            let result = if ret != SQLITE_OK {
+                Err(Error::Configuration(
+                    format!("sqlite3_blob_read() failed: {ret}").into(),
+                ))
+            } else {
+                Ok(buf)
+            };
+            let _ = tx.send(result);
+        }));
+
+        Box::pin(recv(rx))
+    }
+
+    fn dispatch_write(&self, offset: i32, buf: Vec<u8>) -> BoxFuture<Result<(), Error>> {
+        let handle = Handle(self.handle);
+        let (tx, rx) = oneshot::channel();
+
+        self.worker.dispatch(Box::new(move || {
+            let handle = handle;
+            let ret = unsafe {
+                sqlite3_blob_write(handle.0, buf.as_ptr() as *const _, buf.len() as c_int, offset)
+            };
+            let result = if ret != SQLITE_OK {
+                Err(Error::Configuration(
+                    format!("sqlite3_blob_write() failed: {ret}").into(),
+                ))
+            } else {
+                Ok(())
+            };
+            let _ = tx.send(result);
+        }));
+
+        Box::pin(recv(rx))
+    }
+}
+
+/// Carries a raw `sqlite3_blob*` into a job run by [`ConnectionWorker::dispatch`]. The job
+/// itself only ever runs on the thread that owns the connection, so this is safe to send there.
+#[derive(Clone, Copy)]
+struct Handle(*mut sqlite3_blob);
+
+unsafe impl Send for Handle {}
+
+async fn recv<T>(rx: oneshot::Receiver<Result<T, Error>>) -> Result<T, Error> {
+    rx.await
+        .map_err(|_| Error::Configuration("SQLite worker thread dropped a blob request".into()))?
+}
+
+fn c_string(s: &str) -> Result<CString, Error> {
+    CString::new(s).map_err(|e| Error::Configuration(e.to_string().into()))
+}
+
+impl Drop for SqliteBlob {
+    fn drop(&mut self) {
+        let handle = Handle(self.handle);
+
+        self.worker.dispatch(Box::new(move || {
+            let handle = handle;
+            unsafe {
+                sqlite3_blob_close(handle.0);
+            }
+        }));
+    }
+}
+
+impl AsyncRead for SqliteBlob {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.pending_read.is_none() {
+            let remaining = (this.size as i64 - this.pos).max(0) as usize;
+            let n = buf.len().min(remaining);
+            if n == 0 {
+                return Poll::Ready(Ok(0));
+            }
+
+            this.pending_read = Some(this.dispatch_read(this.pos as i32, n));
+        }
+
+        let fut = this.pending_read.as_mut().expect("set above");
+        match fut.as_mut().poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => {
+                this.pending_read = None;
+                let data = result.map_err(io::Error::other)?;
+                let n = data.len();
+                buf[..n].copy_from_slice(&data);
+                this.pos += n as i64;
+                Poll::Ready(Ok(n))
+            }
+        }
+    }
+}
+
+impl AsyncWrite for SqliteBlob {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = self.get_mut();
+
+        if this.pending_write.is_none() {
+            let remaining = (this.size as i64 - this.pos).max(0) as usize;
+            let n = buf.len().min(remaining);
+            if n == 0 {
+                return Poll::Ready(Ok(0));
+            }
+
+            let fut = this.dispatch_write(this.pos as i32, buf[..n].to_vec());
+            this.pending_write = Some((n, fut));
+        }
+
+        let (n, fut) = this.pending_write.as_mut().expect("set above");
+        let n = *n;
+        match fut.as_mut().poll(cx) {
+            Poll::Pending => Poll::Pending,
+            Poll::Ready(result) => {
+                this.pending_write = None;
+                result.map_err(io::Error::other)?;
+                this.pos += n as i64;
+                Poll::Ready(Ok(n))
+            }
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        // There's no separate flush step; `sqlite3_blob_write` writes through immediately.
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl AsyncSeek for SqliteBlob {
+    fn poll_seek(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        pos: io::SeekFrom,
+    ) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+
+        let new_pos = match pos {
+            io::SeekFrom::Start(offset) => offset as i64,
+            io::SeekFrom::End(offset) => this.size as i64 + offset,
+            io::SeekFrom::Current(offset) => this.pos + offset,
+        };
+
+        if new_pos < 0 {
+            return Poll::Ready(Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "seek to a negative position",
+            )));
+        }
+
+        this.pos = new_pos;
+        Poll::Ready(Ok(new_pos as u64))
+    }
+}
@@ -0,0 +1,50 @@
+use std::os::raw::{c_int, c_void};
+use std::panic::catch_unwind;
+use std::process::abort;
+use std::sync::Arc;
+
+use libsqlite3_sys::{sqlite3, sqlite3_busy_handler};
+
+/// A custom [`SQLITE_BUSY`](https://www.sqlite.org/rescode.html#busy) retry policy, as
+/// registered through
+/// [`SqliteConnectOptions::busy_handler`][crate::sqlite::SqliteConnectOptions::busy_handler].
+///
+/// Wraps an `Fn(u32) -> bool` so `SqliteConnectOptions` can stay `Clone + Debug` even though
+/// closures aren't.
+#[derive(Clone)]
+pub(crate) struct BusyHandler(Arc<dyn Fn(u32) -> bool + Send + Sync + 'static>);
+
+impl BusyHandler {
+    pub(crate) fn new(handler: impl Fn(u32) -> bool + Send + Sync + 'static) -> Self {
+        BusyHandler(Arc::new(handler))
+    }
+}
+
+impl std::fmt::Debug for BusyHandler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("BusyHandler").finish()
+    }
+}
+
+/// Install `handler` as the connection's `sqlite3_busy_handler` callback, replacing whatever
+/// `sqlite3_busy_timeout` or a previous `sqlite3_busy_handler` call had set; the two APIs share
+/// the same slot in SQLite, so the one set last wins.
+///
+/// # Safety
+/// `conn` must be a live, exclusively-owned `sqlite3*` (i.e. called from the worker thread).
+///
+/// The boxed `handler` passed to SQLite as user data is never freed by this function, including
+/// when a later call replaces it, so it outlives every callback invocation.
+pub(crate) unsafe fn set_busy_handler(conn: *mut sqlite3, handler: BusyHandler) {
+    let data = Box::into_raw(Box::new(handler)) as *mut c_void;
+    sqlite3_busy_handler(conn, Some(busy_handler_trampoline), data);
+}
+
+unsafe extern "C" fn busy_handler_trampoline(data: *mut c_void, attempts: c_int) -> c_int {
+    let handler = &*(data as *const BusyHandler);
+
+    match catch_unwind(|| (handler.0)(attempts as u32)) {
+        Ok(keep_retrying) => keep_retrying as c_int,
+        Err(_) => abort(),
+    }
+}
@@ -0,0 +1,29 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
+/// A custom collating sequence, as registered through
+/// [`SqliteConnectOptions::collation`][crate::sqlite::SqliteConnectOptions::collation].
+#[derive(Clone)]
+pub(crate) struct Collation {
+    pub(crate) name: Arc<str>,
+    pub(crate) collate: Arc<dyn Fn(&str, &str) -> Ordering + Send + Sync + 'static>,
+}
+
+impl Collation {
+    pub(crate) fn new<N, F>(name: N, collate: F) -> Self
+    where
+        N: Into<Arc<str>>,
+        F: Fn(&str, &str) -> Ordering + Send + Sync + 'static,
+    {
+        Collation {
+            name: name.into(),
+            collate: Arc::new(collate),
+        }
+    }
+}
+
+impl std::fmt::Debug for Collation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Collation").field("name", &self.name).finish()
+    }
+}
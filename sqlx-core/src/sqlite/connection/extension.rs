@@ -0,0 +1,111 @@
+use std::ffi::{CStr, CString};
+use std::ptr;
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_db_config, sqlite3_free, sqlite3_load_extension, SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION,
+    SQLITE_OK,
+};
+
+use crate::error::Error;
+
+/// A SQLite [loadable extension](https://www.sqlite.org/loadext.html) requested through
+/// [`SqliteConnectOptions::extension`][crate::sqlite::SqliteConnectOptions::extension] or
+/// [`extension_with_entrypoint`][crate::sqlite::SqliteConnectOptions::extension_with_entrypoint].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub(crate) struct Extension {
+    pub(crate) name: String,
+    pub(crate) entrypoint: Option<String>,
+}
+
+impl Extension {
+    pub(crate) fn new(name: impl Into<String>) -> Self {
+        Extension {
+            name: name.into(),
+            entrypoint: None,
+        }
+    }
+
+    pub(crate) fn with_entrypoint(name: impl Into<String>, entrypoint: impl Into<String>) -> Self {
+        Extension {
+            name: name.into(),
+            entrypoint: Some(entrypoint.into()),
+        }
+    }
+}
+
+/// Load every requested extension into `conn`, enabling `SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION`
+/// only for the duration of the loop so the capability isn't left on for arbitrary SQL
+/// afterwards (a loaded extension can itself call back into `load_extension()`, so we can't
+/// just flip it off before `sqlite3_load_extension` returns).
+pub(crate) fn load_extensions(conn: *mut sqlite3, extensions: &[Extension]) -> Result<(), Error> {
+    if extensions.is_empty() {
+        return Ok(());
+    }
+
+    unsafe {
+        enable_load_extension(conn, true)?;
+
+        let result = (|| {
+            for extension in extensions {
+                load_one(conn, extension)?;
+            }
+            Ok(())
+        })();
+
+        // Always try to turn it back off, even if a load failed above.
+        enable_load_extension(conn, false)?;
+
+        result
+    }
+}
+
+unsafe fn enable_load_extension(conn: *mut sqlite3, on: bool) -> Result<(), Error> {
+    let ret = sqlite3_db_config(
+        conn,
+        SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION,
+        on as i32,
+        ptr::null_mut::<i32>(),
+    );
+
+    if ret != SQLITE_OK {
+        return Err(Error::Configuration(
+            format!("failed to toggle SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION: {ret}").into(),
+        ));
+    }
+
+    Ok(())
+}
+
+unsafe fn load_one(conn: *mut sqlite3, extension: &Extension) -> Result<(), Error> {
+    let name = CString::new(extension.name.as_str())
+        .map_err(|e| Error::Configuration(e.to_string().into()))?;
+    let entrypoint = extension
+        .entrypoint
+        .as_deref()
+        .map(CString::new)
+        .transpose()
+        .map_err(|e| Error::Configuration(e.to_string().into()))?;
+
+    let mut errmsg: *mut std::os::raw::c_char = ptr::null_mut();
+
+    let ret = sqlite3_load_extension(
+        conn,
+        name.as_ptr(),
+        entrypoint.as_ref().map_or(ptr::null(), |e| e.as_ptr()),
+        &mut errmsg,
+    );
+
+    if ret != SQLITE_OK {
+        let message = if errmsg.is_null() {
+            format!("failed to load extension {:?}", extension.name)
+        } else {
+            let message = CStr::from_ptr(errmsg).to_string_lossy().into_owned();
+            sqlite3_free(errmsg as *mut _);
+            message
+        };
+
+        return Err(Error::Configuration(message.into()));
+    }
+
+    Ok(())
+}
@@ -0,0 +1,391 @@
+use std::os::raw::{c_int, c_void};
+use std::panic::catch_unwind;
+use std::process::abort;
+use std::slice;
+use std::sync::Arc;
+
+use libsqlite3_sys::{
+    sqlite3_aggregate_context, sqlite3_context, sqlite3_result_blob, sqlite3_result_double,
+    sqlite3_result_error, sqlite3_result_int64, sqlite3_result_null, sqlite3_result_text,
+    sqlite3_value, sqlite3_value_blob, sqlite3_value_bytes, sqlite3_value_double,
+    sqlite3_value_int64, sqlite3_value_text, sqlite3_value_type, SQLITE_BLOB, SQLITE_FLOAT,
+    SQLITE_INTEGER, SQLITE_NULL, SQLITE_TEXT, SQLITE_TRANSIENT,
+};
+
+/// A value bound to a user-defined SQL function, as read off the raw `sqlite3_value*`.
+#[derive(Debug, Clone)]
+pub enum SqliteValue {
+    Null,
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl SqliteValue {
+    /// # Safety
+    /// `value` must point to a live `sqlite3_value` for the duration of the call.
+    unsafe fn from_raw(value: *mut sqlite3_value) -> Self {
+        match sqlite3_value_type(value) {
+            SQLITE_INTEGER => SqliteValue::Integer(sqlite3_value_int64(value)),
+            SQLITE_FLOAT => SqliteValue::Real(sqlite3_value_double(value)),
+            SQLITE_TEXT => {
+                let ptr = sqlite3_value_text(value);
+                let len = sqlite3_value_bytes(value) as usize;
+                let bytes = slice::from_raw_parts(ptr, len);
+                SqliteValue::Text(String::from_utf8_lossy(bytes).into_owned())
+            }
+            SQLITE_BLOB => {
+                let ptr = sqlite3_value_blob(value) as *const u8;
+                let len = sqlite3_value_bytes(value) as usize;
+                SqliteValue::Blob(if ptr.is_null() {
+                    Vec::new()
+                } else {
+                    slice::from_raw_parts(ptr, len).to_vec()
+                })
+            }
+            SQLITE_NULL | _ => SqliteValue::Null,
+        }
+    }
+}
+
+/// Anything that can be returned from a user-defined SQL function.
+pub trait ToSqliteResult {
+    /// # Safety
+    /// `ctx` must be a live `sqlite3_context*` for a call currently in progress.
+    unsafe fn set_result(self, ctx: *mut sqlite3_context);
+}
+
+macro_rules! impl_to_sqlite_result_int (
+    ($ty:ty) => {
+        impl ToSqliteResult for $ty {
+            unsafe fn set_result(self, ctx: *mut sqlite3_context) {
+                sqlite3_result_int64(ctx, self as i64);
+            }
+        }
+    }
+);
+
+impl_to_sqlite_result_int!(i8);
+impl_to_sqlite_result_int!(i16);
+impl_to_sqlite_result_int!(i32);
+impl_to_sqlite_result_int!(i64);
+impl_to_sqlite_result_int!(u8);
+impl_to_sqlite_result_int!(u16);
+impl_to_sqlite_result_int!(u32);
+
+impl ToSqliteResult for f64 {
+    unsafe fn set_result(self, ctx: *mut sqlite3_context) {
+        sqlite3_result_double(ctx, self);
+    }
+}
+
+impl ToSqliteResult for String {
+    unsafe fn set_result(self, ctx: *mut sqlite3_context) {
+        let len = self.len() as c_int;
+        // `SQLITE_TRANSIENT` tells SQLite to copy the bytes, since `self` is dropped on return.
+        sqlite3_result_text(
+            ctx,
+            self.as_ptr() as *const _,
+            len,
+            SQLITE_TRANSIENT(),
+        );
+    }
+}
+
+impl ToSqliteResult for Vec<u8> {
+    unsafe fn set_result(self, ctx: *mut sqlite3_context) {
+        let len = self.len() as c_int;
+        sqlite3_result_blob(ctx, self.as_ptr() as *const _, len, SQLITE_TRANSIENT());
+    }
+}
+
+impl<T: ToSqliteResult> ToSqliteResult for Option<T> {
+    unsafe fn set_result(self, ctx: *mut sqlite3_context) {
+        match self {
+            Some(value) => value.set_result(ctx),
+            None => sqlite3_result_null(ctx),
+        }
+    }
+}
+
+impl<T: ToSqliteResult, E: ToString> ToSqliteResult for Result<T, E> {
+    unsafe fn set_result(self, ctx: *mut sqlite3_context) {
+        match self {
+            Ok(value) => value.set_result(ctx),
+            Err(e) => {
+                let msg = e.to_string();
+                sqlite3_result_error(ctx, msg.as_ptr() as *const _, msg.len() as c_int);
+            }
+        }
+    }
+}
+
+/// # Safety
+/// `argc`/`argv` must describe a live argument array for the duration of the call.
+unsafe fn args(argc: c_int, argv: *mut *mut sqlite3_value) -> Vec<SqliteValue> {
+    slice::from_raw_parts(argv, argc as usize)
+        .iter()
+        .map(|&value| SqliteValue::from_raw(value))
+        .collect()
+}
+
+/// A user-defined scalar function, as registered through
+/// [`SqliteConnectOptions::function`][crate::sqlite::SqliteConnectOptions::function].
+#[derive(Clone)]
+pub(crate) struct ScalarFunction {
+    pub(crate) name: Arc<str>,
+    pub(crate) num_args: i32,
+    call: Arc<dyn Fn(&[SqliteValue]) -> Box<dyn ScalarResult> + Send + Sync + 'static>,
+}
+
+/// Type-erased wrapper so `ScalarFunction` doesn't need to be generic over its return type.
+pub(crate) trait ScalarResult {
+    /// # Safety
+    /// `ctx` must be a live `sqlite3_context*` for a call currently in progress.
+    unsafe fn apply(self: Box<Self>, ctx: *mut sqlite3_context);
+}
+
+impl<T: ToSqliteResult> ScalarResult for T {
+    unsafe fn apply(self: Box<Self>, ctx: *mut sqlite3_context) {
+        (*self).set_result(ctx);
+    }
+}
+
+impl ScalarFunction {
+    pub(crate) fn new<F, R>(name: impl Into<Arc<str>>, num_args: i32, call: F) -> Self
+    where
+        F: Fn(&[SqliteValue]) -> R + Send + Sync + 'static,
+        R: ToSqliteResult + 'static,
+    {
+        ScalarFunction {
+            name: name.into(),
+            num_args,
+            call: Arc::new(move |args| Box::new(call(args))),
+        }
+    }
+}
+
+impl std::fmt::Debug for ScalarFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ScalarFunction")
+            .field("name", &self.name)
+            .field("num_args", &self.num_args)
+            .finish()
+    }
+}
+
+pub(crate) unsafe extern "C" fn scalar_call(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let function: *const ScalarFunction =
+        libsqlite3_sys::sqlite3_user_data(ctx) as *const ScalarFunction;
+
+    let result = catch_unwind(|| {
+        let args = args(argc, argv);
+        ((*function).call)(&args)
+    });
+
+    match result {
+        Ok(result) => result.apply(ctx),
+        Err(_) => abort(),
+    }
+}
+
+pub(crate) unsafe extern "C" fn free_boxed_value<T>(ptr: *mut c_void) {
+    drop(Box::from_raw(ptr as *mut T));
+}
+
+/// A user-defined aggregate function, as registered through
+/// [`SqliteConnectOptions::aggregate_function`][crate::sqlite::SqliteConnectOptions::aggregate_function].
+///
+/// Per-group state lives behind `sqlite3_aggregate_context`, which SQLite zero-allocates the
+/// first time `xStep` or `xFinal` is called for a given group and frees once `xFinal` returns.
+#[derive(Clone)]
+pub(crate) struct AggregateFunction {
+    pub(crate) name: Arc<str>,
+    pub(crate) num_args: i32,
+    init: Arc<dyn Fn() -> Box<dyn std::any::Any + Send> + Send + Sync>,
+    step: Arc<dyn Fn(&mut (dyn std::any::Any + Send), &[SqliteValue]) + Send + Sync>,
+    finish: Arc<dyn Fn(Box<dyn std::any::Any + Send>) -> Box<dyn ScalarResult> + Send + Sync>,
+}
+
+impl AggregateFunction {
+    pub(crate) fn new<S, S0, Step, Finish, R>(
+        name: impl Into<Arc<str>>,
+        num_args: i32,
+        init: S0,
+        step: Step,
+        finish: Finish,
+    ) -> Self
+    where
+        S: Send + 'static,
+        S0: Fn() -> S + Send + Sync + 'static,
+        Step: Fn(&mut S, &[SqliteValue]) + Send + Sync + 'static,
+        Finish: Fn(S) -> R + Send + Sync + 'static,
+        R: ToSqliteResult + 'static,
+    {
+        AggregateFunction {
+            name: name.into(),
+            num_args,
+            init: Arc::new(move || Box::new(init()) as Box<dyn std::any::Any + Send>),
+            step: Arc::new(move |state, args| step(state.downcast_mut::<S>().expect("state type"), args)),
+            finish: Arc::new(move |state| {
+                let state = *state.downcast::<S>().expect("state type");
+                Box::new(finish(state)) as Box<dyn ScalarResult>
+            }),
+        }
+    }
+}
+
+impl std::fmt::Debug for AggregateFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AggregateFunction")
+            .field("name", &self.name)
+            .field("num_args", &self.num_args)
+            .finish()
+    }
+}
+
+/// Slot SQLite allocates for us via `sqlite3_aggregate_context`; holds the boxed, type-erased
+/// per-group state until `xFinal` takes it.
+struct AggregateSlot {
+    state: Option<Box<dyn std::any::Any + Send>>,
+}
+
+unsafe fn aggregate_slot<'a>(ctx: *mut sqlite3_context) -> &'a mut AggregateSlot {
+    let slot = sqlite3_aggregate_context(ctx, std::mem::size_of::<AggregateSlot>() as c_int)
+        as *mut AggregateSlot;
+    &mut *slot
+}
+
+pub(crate) unsafe extern "C" fn aggregate_step(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let function: *const AggregateFunction =
+        libsqlite3_sys::sqlite3_user_data(ctx) as *const AggregateFunction;
+
+    let result = catch_unwind(|| {
+        let slot = aggregate_slot(ctx);
+        let state = slot.state.get_or_insert_with(|| ((*function).init)());
+        let args = args(argc, argv);
+        ((*function).step)(&mut **state, &args);
+    });
+
+    if result.is_err() {
+        abort();
+    }
+}
+
+pub(crate) unsafe extern "C" fn aggregate_final(ctx: *mut sqlite3_context) {
+    let function: *const AggregateFunction =
+        libsqlite3_sys::sqlite3_user_data(ctx) as *const AggregateFunction;
+
+    let result = catch_unwind(|| {
+        let slot = aggregate_slot(ctx);
+        // A group with zero rows never calls `xStep`; fall back to a freshly initialized state.
+        let state = slot.state.take().unwrap_or_else(|| ((*function).init)());
+        ((*function).finish)(state)
+    });
+
+    match result {
+        Ok(result) => result.apply(ctx),
+        Err(_) => abort(),
+    }
+}
+
+/// A user-defined window function, registered through
+/// [`SqliteConnectOptions::window_function`][crate::sqlite::SqliteConnectOptions::window_function].
+///
+/// In addition to `xStep`/`xFinal`, window functions need `xValue` (report the current
+/// aggregate value without consuming it, as the frame slides) and `xInverse` (undo a row that
+/// has left the frame), so the per-group state must be `Clone`.
+#[derive(Clone)]
+pub(crate) struct WindowFunction {
+    pub(crate) aggregate: AggregateFunction,
+    inverse: Arc<dyn Fn(&mut (dyn std::any::Any + Send), &[SqliteValue]) + Send + Sync>,
+    value: Arc<dyn Fn(&(dyn std::any::Any + Send)) -> Box<dyn ScalarResult> + Send + Sync>,
+}
+
+impl WindowFunction {
+    pub(crate) fn new<S, S0, Step, Inverse, Value, Finish, R>(
+        name: impl Into<Arc<str>>,
+        num_args: i32,
+        init: S0,
+        step: Step,
+        inverse: Inverse,
+        value: Value,
+        finish: Finish,
+    ) -> Self
+    where
+        S: Clone + Send + 'static,
+        S0: Fn() -> S + Send + Sync + 'static,
+        Step: Fn(&mut S, &[SqliteValue]) + Send + Sync + 'static,
+        Inverse: Fn(&mut S, &[SqliteValue]) + Send + Sync + 'static,
+        Value: Fn(&S) -> R + Send + Sync + 'static,
+        Finish: Fn(S) -> R + Send + Sync + 'static,
+        R: ToSqliteResult + 'static,
+    {
+        WindowFunction {
+            aggregate: AggregateFunction::new(name, num_args, init, step, finish),
+            inverse: Arc::new(move |state, args| {
+                inverse(state.downcast_mut::<S>().expect("state type"), args)
+            }),
+            value: Arc::new(move |state| {
+                Box::new(value(state.downcast_ref::<S>().expect("state type")))
+                    as Box<dyn ScalarResult>
+            }),
+        }
+    }
+}
+
+impl std::fmt::Debug for WindowFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("WindowFunction")
+            .field("name", &self.aggregate.name)
+            .field("num_args", &self.aggregate.num_args)
+            .finish()
+    }
+}
+
+pub(crate) unsafe extern "C" fn window_inverse(
+    ctx: *mut sqlite3_context,
+    argc: c_int,
+    argv: *mut *mut sqlite3_value,
+) {
+    let function: *const WindowFunction = libsqlite3_sys::sqlite3_user_data(ctx) as *const WindowFunction;
+
+    let result = catch_unwind(|| {
+        let slot = aggregate_slot(ctx);
+        let state = slot
+            .state
+            .get_or_insert_with(|| ((*function).aggregate.init)());
+        let args = args(argc, argv);
+        ((*function).inverse)(&mut **state, &args);
+    });
+
+    if result.is_err() {
+        abort();
+    }
+}
+
+pub(crate) unsafe extern "C" fn window_value(ctx: *mut sqlite3_context) {
+    let function: *const WindowFunction = libsqlite3_sys::sqlite3_user_data(ctx) as *const WindowFunction;
+
+    let result = catch_unwind(|| {
+        let slot = aggregate_slot(ctx);
+        let state = slot
+            .state
+            .get_or_insert_with(|| ((*function).aggregate.init)());
+        ((*function).value)(&**state)
+    });
+
+    match result {
+        Ok(result) => result.apply(ctx),
+        Err(_) => abort(),
+    }
+}
@@ -0,0 +1,147 @@
+use std::os::raw::{c_char, c_int, c_void};
+use std::panic::catch_unwind;
+use std::process::abort;
+use std::sync::Arc;
+
+use libsqlite3_sys::{
+    sqlite3, sqlite3_commit_hook, sqlite3_rollback_hook, sqlite3_update_hook, SQLITE_DELETE,
+    SQLITE_INSERT, SQLITE_UPDATE,
+};
+
+/// The kind of row change reported to an [`update_hook`](UpdateHookFn).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UpdateHookOp {
+    Insert,
+    Update,
+    Delete,
+}
+
+impl UpdateHookOp {
+    fn from_raw(op: c_int) -> Option<Self> {
+        match op as u32 {
+            SQLITE_INSERT => Some(UpdateHookOp::Insert),
+            SQLITE_UPDATE => Some(UpdateHookOp::Update),
+            SQLITE_DELETE => Some(UpdateHookOp::Delete),
+            _ => None,
+        }
+    }
+}
+
+pub(crate) type UpdateHookFn = dyn Fn(UpdateHookOp, &str, &str, i64) + Send + Sync + 'static;
+/// Returning `true` vetoes the commit, turning it into a rollback.
+pub(crate) type CommitHookFn = dyn Fn() -> bool + Send + Sync + 'static;
+pub(crate) type RollbackHookFn = dyn Fn() + Send + Sync + 'static;
+
+/// Install `hook` as the connection's `sqlite3_update_hook` callback, replacing any previous
+/// one. Pass `None` to remove it.
+///
+/// # Safety
+/// `conn` must be a live, exclusively-owned `sqlite3*` (i.e. called from the worker thread).
+pub(crate) unsafe fn set_update_hook(conn: *mut sqlite3, hook: Option<Arc<UpdateHookFn>>) {
+    let trampoline: Option<
+        unsafe extern "C" fn(*mut c_void, c_int, *const c_char, *const c_char, i64),
+    > = hook.is_some().then_some(update_hook_trampoline);
+
+    set_hook(
+        hook,
+        |conn, data| sqlite3_update_hook(conn, trampoline, data),
+        conn,
+    );
+}
+
+/// Install `hook` as the connection's `sqlite3_commit_hook` callback, replacing any previous
+/// one. Pass `None` to remove it.
+///
+/// # Safety
+/// `conn` must be a live, exclusively-owned `sqlite3*` (i.e. called from the worker thread).
+pub(crate) unsafe fn set_commit_hook(conn: *mut sqlite3, hook: Option<Arc<CommitHookFn>>) {
+    let trampoline: Option<unsafe extern "C" fn(*mut c_void) -> c_int> =
+        hook.is_some().then_some(commit_hook_trampoline);
+
+    set_hook(
+        hook,
+        |conn, data| sqlite3_commit_hook(conn, trampoline, data),
+        conn,
+    );
+}
+
+/// Install `hook` as the connection's `sqlite3_rollback_hook` callback, replacing any previous
+/// one. Pass `None` to remove it.
+///
+/// # Safety
+/// `conn` must be a live, exclusively-owned `sqlite3*` (i.e. called from the worker thread).
+pub(crate) unsafe fn set_rollback_hook(conn: *mut sqlite3, hook: Option<Arc<RollbackHookFn>>) {
+    let trampoline: Option<unsafe extern "C" fn(*mut c_void)> =
+        hook.is_some().then_some(rollback_hook_trampoline);
+
+    set_hook(
+        hook,
+        |conn, data| sqlite3_rollback_hook(conn, trampoline, data),
+        conn,
+    );
+}
+
+/// Shared plumbing for the three `sqlite3_*_hook` setters: box the new callback (if any) and
+/// hand SQLite the raw pointer.
+///
+/// Every `sqlite3_*_hook` call returns the *previous* user data pointer instead of taking a
+/// destructor callback, so `install` must forward that return value here so we can box it back
+/// up and drop it — otherwise every call that replaces (or removes) a hook leaks the old one.
+unsafe fn set_hook<T: ?Sized>(
+    hook: Option<Arc<T>>,
+    install: impl FnOnce(*mut sqlite3, *mut c_void) -> *mut c_void,
+    conn: *mut sqlite3,
+) {
+    let data = match hook {
+        Some(hook) => Box::into_raw(Box::new(hook)) as *mut c_void,
+        None => std::ptr::null_mut(),
+    };
+
+    let previous = install(conn, data);
+
+    if !previous.is_null() {
+        // SAFETY: any non-null pointer SQLite hands back here was produced by a prior call to
+        // this function with the same `T`, boxing up an `Arc<T>` as its user data.
+        drop(Box::from_raw(previous as *mut Arc<T>));
+    }
+}
+
+unsafe extern "C" fn update_hook_trampoline(
+    data: *mut c_void,
+    op: c_int,
+    db_name: *const c_char,
+    table_name: *const c_char,
+    rowid: i64,
+) {
+    let hook = &*(data as *const Arc<UpdateHookFn>);
+
+    let result = catch_unwind(|| {
+        let Some(op) = UpdateHookOp::from_raw(op) else {
+            return;
+        };
+        let db_name = std::ffi::CStr::from_ptr(db_name).to_string_lossy();
+        let table_name = std::ffi::CStr::from_ptr(table_name).to_string_lossy();
+        hook(op, &db_name, &table_name, rowid);
+    });
+
+    if result.is_err() {
+        abort();
+    }
+}
+
+unsafe extern "C" fn commit_hook_trampoline(data: *mut c_void) -> c_int {
+    let hook = &*(data as *const Arc<CommitHookFn>);
+
+    match catch_unwind(|| hook()) {
+        Ok(veto) => veto as c_int,
+        Err(_) => abort(),
+    }
+}
+
+unsafe extern "C" fn rollback_hook_trampoline(data: *mut c_void) {
+    let hook = &*(data as *const Arc<RollbackHookFn>);
+
+    if catch_unwind(|| hook()).is_err() {
+        abort();
+    }
+}
@@ -0,0 +1,8 @@
+pub(crate) mod backup;
+pub(crate) mod blob;
+pub(crate) mod busy_handler;
+pub(crate) mod collation;
+pub(crate) mod extension;
+pub(crate) mod function;
+pub(crate) mod hooks;
+pub(crate) mod session;
@@ -0,0 +1,457 @@
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+use std::os::raw::c_int;
+use std::ptr;
+use std::ptr::NonNull;
+
+use indexmap::map::IndexMap;
+use libsqlite3_sys::{
+    sqlite3, sqlite3_changeset_apply, sqlite3_changeset_finalize, sqlite3_changeset_iter,
+    sqlite3_changeset_new, sqlite3_changeset_next, sqlite3_changeset_old, sqlite3_changeset_op,
+    sqlite3_changeset_pk, sqlite3_changeset_start, sqlite3_column_text, sqlite3_finalize,
+    sqlite3_free, sqlite3_prepare_v2, sqlite3_session, sqlite3_step, sqlite3_stmt,
+    sqlite3session_attach, sqlite3session_changeset, sqlite3session_create, sqlite3session_delete,
+    SQLITE_DELETE, SQLITE_INSERT, SQLITE_OK, SQLITE_ROW, SQLITE_UPDATE,
+};
+
+use crate::testing::fixtures::{ColumnName, Fixture, FixtureError, FixtureOp, Result, TableName};
+
+/// Records every row-level change made to `main` while it's attached, by wrapping SQLite's
+/// [session extension](https://www.sqlite.org/sessionintro.html).
+///
+/// Dropping the guard tears down the underlying `sqlite3_session*` without error; call
+/// [`Session::changeset`] beforehand to capture what was recorded.
+pub(crate) struct Session {
+    handle: NonNull<sqlite3_session>,
+    conn: NonNull<sqlite3>,
+}
+
+// The session handle is only ever touched from the worker thread that owns the connection.
+unsafe impl Send for Session {}
+
+impl Session {
+    /// Create and attach a session that records changes to every table in `main`.
+    pub(crate) fn new(conn: *mut sqlite3) -> Result<Self> {
+        unsafe {
+            let mut session: *mut sqlite3_session = ptr::null_mut();
+
+            let main = CString::new("main").expect("no nul bytes in \"main\"");
+            let ret = sqlite3session_create(conn, main.as_ptr(), &mut session);
+            if ret != SQLITE_OK {
+                return Err(FixtureError::new(format!(
+                    "sqlite3session_create() failed: {ret}"
+                )));
+            }
+
+            // A `NULL` table name attaches every table in the schema, present or future.
+            let ret = sqlite3session_attach(session, ptr::null());
+            if ret != SQLITE_OK {
+                sqlite3session_delete(session);
+                return Err(FixtureError::new(format!(
+                    "sqlite3session_attach() failed: {ret}"
+                )));
+            }
+
+            Ok(Self {
+                handle: NonNull::new_unchecked(session),
+                conn: NonNull::new_unchecked(conn),
+            })
+        }
+    }
+
+    /// Capture everything recorded so far as a binary changeset and turn it into a [`Fixture`].
+    pub(crate) fn changeset(&self) -> Result<Fixture> {
+        let (len, buf) = self.capture_changeset()?;
+
+        // SAFETY: `buf`/`len` were just populated by `sqlite3session_changeset()` and are
+        // owned by us until we free them with `sqlite3_free` (done by `decode_changeset`).
+        unsafe { decode_changeset(self.conn.as_ptr(), buf, len) }
+    }
+
+    /// Capture everything recorded so far as a raw binary changeset, e.g. to replay into
+    /// another database with [`apply`] — unlike [`changeset`][Self::changeset], this doesn't
+    /// require the recording connection's schema to resolve column names.
+    pub(crate) fn raw_changeset(&self) -> Result<Vec<u8>> {
+        let (len, buf) = self.capture_changeset()?;
+
+        // SAFETY: `buf`/`len` were just populated by `sqlite3session_changeset()`; copy them
+        // into an owned buffer before freeing the native allocation.
+        let bytes =
+            unsafe { std::slice::from_raw_parts(buf as *const u8, len as usize) }.to_vec();
+        unsafe { sqlite3_free(buf) };
+
+        Ok(bytes)
+    }
+
+    fn capture_changeset(&self) -> Result<(c_int, *mut std::os::raw::c_void)> {
+        let mut len: c_int = 0;
+        let mut buf: *mut std::os::raw::c_void = ptr::null_mut();
+
+        let ret = unsafe { sqlite3session_changeset(self.handle.as_ptr(), &mut len, &mut buf) };
+        if ret != SQLITE_OK {
+            return Err(FixtureError::new(format!(
+                "sqlite3session_changeset() failed: {ret}"
+            )));
+        }
+
+        Ok((len, buf))
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        unsafe { sqlite3session_delete(self.handle.as_ptr()) }
+    }
+}
+
+/// Finalizes `iter` and frees `buf` on every exit path (success, a `sqlite3changeset_next()`
+/// failure, or `?` bubbling a `decode_change()` error) so a mid-loop error can't leak either.
+struct ChangesetIter {
+    iter: *mut sqlite3_changeset_iter,
+    buf: *mut std::os::raw::c_void,
+}
+
+impl Drop for ChangesetIter {
+    fn drop(&mut self) {
+        unsafe {
+            sqlite3_changeset_finalize(self.iter);
+            sqlite3_free(self.buf);
+        }
+    }
+}
+
+/// Iterate a raw changeset blob, mapping each change to a [`FixtureOp`].
+///
+/// # Safety
+/// `buf` must point to `len` bytes produced by `sqlite3session_changeset()` or
+/// `sqlite3changeset_apply()`'s conflict handler, and ownership of that allocation is
+/// transferred to this function (it is freed via `sqlite3_free` before returning).
+/// `conn` must be the connection the changeset was recorded against, used to resolve column
+/// names via `PRAGMA table_info`.
+unsafe fn decode_changeset(
+    conn: *mut sqlite3,
+    buf: *mut std::os::raw::c_void,
+    len: c_int,
+) -> Result<Fixture> {
+    let mut iter: *mut sqlite3_changeset_iter = ptr::null_mut();
+    let ret = sqlite3_changeset_start(&mut iter, len, buf);
+    if ret != SQLITE_OK {
+        sqlite3_free(buf);
+        return Err(FixtureError::new(format!(
+            "sqlite3changeset_start() failed: {ret}"
+        )));
+    }
+
+    // From here on, `iter`/`buf` are released by this guard, not by hand, so that the `?` in
+    // the loop below can't skip cleanup.
+    let guard = ChangesetIter { iter, buf };
+
+    let mut ops = Vec::new();
+    let mut columns_by_table: HashMap<TableName, Vec<ColumnName>> = HashMap::new();
+
+    loop {
+        let ret = sqlite3_changeset_next(guard.iter);
+        if ret == libsqlite3_sys::SQLITE_DONE {
+            break;
+        }
+        if ret != SQLITE_ROW {
+            return Err(FixtureError::new(format!(
+                "sqlite3changeset_next() failed: {ret}"
+            )));
+        }
+
+        ops.push(decode_change(conn, guard.iter, &mut columns_by_table)?);
+    }
+
+    Ok(Fixture::from_ops(ops))
+}
+
+unsafe fn decode_change(
+    conn: *mut sqlite3,
+    iter: *mut sqlite3_changeset_iter,
+    columns_by_table: &mut HashMap<TableName, Vec<ColumnName>>,
+) -> Result<FixtureOp> {
+    let mut table_name: *const std::os::raw::c_char = ptr::null();
+    let mut num_cols: c_int = 0;
+    let mut op: c_int = 0;
+    let mut indirect: c_int = 0;
+
+    let ret = sqlite3_changeset_op(iter, &mut table_name, &mut num_cols, &mut op, &mut indirect);
+    if ret != SQLITE_OK {
+        return Err(FixtureError::new(format!(
+            "sqlite3changeset_op() failed: {ret}"
+        )));
+    }
+
+    let table: TableName = CStr::from_ptr(table_name).to_string_lossy().into_owned().into();
+
+    // Tables must declare a `PRIMARY KEY` for the session extension to record identifying
+    // columns on UPDATE/DELETE; a table with only a rowid has no such column.
+    let mut pk_cols: *mut u8 = ptr::null_mut();
+    let mut pk_num_cols: c_int = 0;
+    let ret = sqlite3_changeset_pk(iter, &mut pk_cols, &mut pk_num_cols);
+    if ret != SQLITE_OK {
+        return Err(FixtureError::new(format!(
+            "sqlite3changeset_pk() failed: {ret}"
+        )));
+    }
+    let pk_cols = std::slice::from_raw_parts(pk_cols, pk_num_cols as usize);
+    if pk_cols.iter().all(|&is_pk| is_pk == 0) {
+        return Err(FixtureError::new(format!(
+            "table {table:?} has no primary key; cannot build a fixture from its changeset"
+        )));
+    }
+
+    // `sqlite3changeset_op()` only gives us a column's index, not its name; resolve names once
+    // per table via `PRAGMA table_info` and reuse them for every change against that table.
+    if !columns_by_table.contains_key(&table) {
+        let columns = table_columns(conn, &table)?;
+        columns_by_table.insert(table.clone(), columns);
+    }
+    let columns = &columns_by_table[&table];
+
+    match op as u32 {
+        SQLITE_INSERT => {
+            let mut out_columns = Vec::new();
+            let mut rows = Vec::new();
+            for i in 0..num_cols {
+                let mut value = ptr::null_mut();
+                if sqlite3_changeset_new(iter, i, &mut value) == SQLITE_OK && !value.is_null() {
+                    out_columns.push(column_name(columns, i)?);
+                    rows.push(value_to_string(value));
+                }
+            }
+            Ok(FixtureOp::Insert {
+                table,
+                columns: out_columns,
+                rows,
+            })
+        }
+        SQLITE_UPDATE => {
+            let mut set = IndexMap::new();
+            let mut cond = IndexMap::new();
+            for i in 0..num_cols {
+                let mut new_value = ptr::null_mut();
+                if sqlite3_changeset_new(iter, i, &mut new_value) == SQLITE_OK
+                    && !new_value.is_null()
+                {
+                    set.insert(column_name(columns, i)?, value_to_string(new_value));
+                }
+
+                if pk_cols[i as usize] != 0 {
+                    let mut old_value = ptr::null_mut();
+                    if sqlite3_changeset_old(iter, i, &mut old_value) == SQLITE_OK
+                        && !old_value.is_null()
+                    {
+                        cond.insert(column_name(columns, i)?, value_to_string(old_value));
+                    }
+                }
+            }
+            Ok(FixtureOp::Update { table, set, cond })
+        }
+        SQLITE_DELETE => {
+            let mut cond = IndexMap::new();
+            for i in 0..num_cols {
+                if pk_cols[i as usize] != 0 {
+                    let mut old_value = ptr::null_mut();
+                    if sqlite3_changeset_old(iter, i, &mut old_value) == SQLITE_OK
+                        && !old_value.is_null()
+                    {
+                        cond.insert(column_name(columns, i)?, value_to_string(old_value));
+                    }
+                }
+            }
+            Ok(FixtureOp::Delete { table, cond })
+        }
+        _ => Err(FixtureError::new(format!("unknown changeset op {op}"))),
+    }
+}
+
+fn column_name(columns: &[ColumnName], index: c_int) -> Result<ColumnName> {
+    columns.get(index as usize).cloned().ok_or_else(|| {
+        FixtureError::new(format!(
+            "column index {index} out of range for table with {} columns",
+            columns.len()
+        ))
+    })
+}
+
+/// Resolve a table's column names, in declaration order, via `PRAGMA table_info`.
+unsafe fn table_columns(conn: *mut sqlite3, table: &str) -> Result<Vec<ColumnName>> {
+    let sql = CString::new(format!("PRAGMA table_info({})", quote_identifier(table)))
+        .map_err(|e| FixtureError::new(e.to_string()))?;
+
+    let mut stmt: *mut sqlite3_stmt = ptr::null_mut();
+    let ret = sqlite3_prepare_v2(conn, sql.as_ptr(), -1, &mut stmt, ptr::null_mut());
+    if ret != SQLITE_OK {
+        return Err(FixtureError::new(format!(
+            "failed to prepare PRAGMA table_info({table:?}): {ret}"
+        )));
+    }
+
+    let mut columns = Vec::new();
+    loop {
+        let ret = sqlite3_step(stmt);
+        if ret == libsqlite3_sys::SQLITE_DONE {
+            break;
+        }
+        if ret != SQLITE_ROW {
+            sqlite3_finalize(stmt);
+            return Err(FixtureError::new(format!(
+                "failed to step PRAGMA table_info({table:?}): {ret}"
+            )));
+        }
+
+        // `table_info`'s column 1 is the column name.
+        let name_ptr = sqlite3_column_text(stmt, 1);
+        let name = if name_ptr.is_null() {
+            String::new()
+        } else {
+            CStr::from_ptr(name_ptr as *const _)
+                .to_string_lossy()
+                .into_owned()
+        };
+        columns.push(name.into());
+    }
+
+    sqlite3_finalize(stmt);
+
+    if columns.is_empty() {
+        return Err(FixtureError::new(format!(
+            "table {table:?} has no columns (or does not exist)"
+        )));
+    }
+
+    Ok(columns)
+}
+
+/// Quote `name` as a SQL identifier so it can be inlined into `PRAGMA table_info(...)`, which
+/// (unlike ordinary statements) doesn't accept the table name as a bound parameter.
+fn quote_identifier(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+unsafe fn value_to_string(value: *mut libsqlite3_sys::sqlite3_value) -> String {
+    let ptr = libsqlite3_sys::sqlite3_value_text(value);
+    if ptr.is_null() {
+        return String::new();
+    }
+    CStr::from_ptr(ptr as *const _).to_string_lossy().into_owned()
+}
+
+/// Replay a changeset (as produced by [`Session::raw_changeset`]) into `conn`.
+pub(crate) fn apply(conn: *mut sqlite3, changeset: &[u8]) -> Result<()> {
+    let ret = unsafe {
+        sqlite3_changeset_apply(
+            conn,
+            changeset.len() as c_int,
+            changeset.as_ptr() as *mut _,
+            None,
+            None,
+            ptr::null_mut(),
+        )
+    };
+
+    if ret != SQLITE_OK {
+        return Err(FixtureError::new(format!(
+            "sqlite3changeset_apply() failed: {ret}"
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::CString;
+    use std::ptr;
+
+    use libsqlite3_sys::{
+        sqlite3_close, sqlite3_column_int64, sqlite3_exec, sqlite3_finalize, sqlite3_open,
+        sqlite3_prepare_v2, sqlite3_step, SQLITE_ROW,
+    };
+
+    use super::*;
+
+    fn open_memory_db() -> *mut sqlite3 {
+        let path = CString::new(":memory:").unwrap();
+        let mut conn: *mut sqlite3 = ptr::null_mut();
+        let ret = unsafe { sqlite3_open(path.as_ptr(), &mut conn) };
+        assert_eq!(ret, SQLITE_OK);
+        conn
+    }
+
+    fn exec(conn: *mut sqlite3, sql: &str) {
+        let sql = CString::new(sql).unwrap();
+        let ret =
+            unsafe { sqlite3_exec(conn, sql.as_ptr(), None, ptr::null_mut(), ptr::null_mut()) };
+        assert_eq!(ret, SQLITE_OK, "failed to execute {sql:?}");
+    }
+
+    fn count_people(conn: *mut sqlite3) -> i64 {
+        let sql = CString::new("SELECT count(*) FROM people").unwrap();
+        let mut stmt = ptr::null_mut();
+        let ret =
+            unsafe { sqlite3_prepare_v2(conn, sql.as_ptr(), -1, &mut stmt, ptr::null_mut()) };
+        assert_eq!(ret, SQLITE_OK);
+        let ret = unsafe { sqlite3_step(stmt) };
+        assert_eq!(ret, SQLITE_ROW);
+        let count = unsafe { sqlite3_column_int64(stmt, 0) };
+        unsafe { sqlite3_finalize(stmt) };
+        count
+    }
+
+    #[test]
+    fn quote_identifier_doubles_embedded_quotes() {
+        assert_eq!(quote_identifier("people"), "\"people\"");
+        assert_eq!(quote_identifier(r#"weird"name"#), "\"weird\"\"name\"");
+    }
+
+    #[test]
+    fn changeset_resolves_real_column_names() {
+        let conn = open_memory_db();
+        exec(conn, "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT)");
+
+        let session = Session::new(conn).expect("session attach");
+        exec(conn, "INSERT INTO people (id, name) VALUES (1, 'Ada')");
+
+        let fixture = session.changeset().expect("decode changeset");
+        match fixture.ops() {
+            [FixtureOp::Insert {
+                table,
+                columns,
+                rows,
+            }] => {
+                assert_eq!(table.as_ref(), "people");
+                assert_eq!(
+                    columns.iter().map(|c| c.to_string()).collect::<Vec<_>>(),
+                    vec!["id".to_string(), "name".to_string()]
+                );
+                assert_eq!(rows, &vec!["1".to_string(), "Ada".to_string()]);
+            }
+            other => panic!("expected a single Insert op, got {} ops", other.len()),
+        }
+
+        drop(session);
+        unsafe { sqlite3_close(conn) };
+    }
+
+    #[test]
+    fn raw_changeset_replays_into_another_connection() {
+        let src = open_memory_db();
+        exec(src, "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT)");
+
+        let session = Session::new(src).expect("session attach");
+        exec(src, "INSERT INTO people (id, name) VALUES (1, 'Ada')");
+        let bytes = session.raw_changeset().expect("capture raw changeset");
+        drop(session);
+        unsafe { sqlite3_close(src) };
+
+        let dest = open_memory_db();
+        exec(dest, "CREATE TABLE people (id INTEGER PRIMARY KEY, name TEXT)");
+        apply(dest, &bytes).expect("apply changeset");
+
+        assert_eq!(count_people(dest), 1);
+        unsafe { sqlite3_close(dest) };
+    }
+}
@@ -0,0 +1,10 @@
+//! Types for the SQLite database driver.
+
+pub(crate) mod connection;
+pub mod options;
+
+pub use connection::backup::BackupProgress;
+pub use connection::blob::SqliteBlob;
+pub use connection::function::SqliteValue;
+pub use connection::hooks::UpdateHookOp;
+pub use options::SqliteConnectOptions;
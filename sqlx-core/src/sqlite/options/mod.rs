@@ -18,6 +18,11 @@ pub use synchronous::SqliteSynchronous;
 
 use crate::common::DebugFn;
 use crate::sqlite::connection::collation::Collation;
+use crate::sqlite::connection::busy_handler::BusyHandler;
+use crate::sqlite::connection::extension::Extension;
+use crate::sqlite::connection::function::{
+    AggregateFunction, ScalarFunction, SqliteValue, ToSqliteResult, WindowFunction,
+};
 use indexmap::IndexMap;
 
 /// Options and flags which can be used to configure a SQLite connection.
@@ -61,6 +66,7 @@ pub struct SqliteConnectOptions {
     pub(crate) shared_cache: bool,
     pub(crate) statement_cache_capacity: usize,
     pub(crate) busy_timeout: Duration,
+    pub(crate) busy_handler: Option<BusyHandler>,
     pub(crate) log_settings: LogSettings,
     pub(crate) immutable: bool,
 
@@ -71,6 +77,12 @@ pub struct SqliteConnectOptions {
 
     pub(crate) collations: Vec<Collation>,
 
+    pub(crate) scalar_functions: Vec<ScalarFunction>,
+    pub(crate) aggregate_functions: Vec<AggregateFunction>,
+    pub(crate) window_functions: Vec<WindowFunction>,
+
+    pub(crate) extensions: Vec<Extension>,
+
     pub(crate) serialized: bool,
     pub(crate) thread_name: Arc<DebugFn<dyn Fn(u64) -> String + Send + Sync + 'static>>,
 }
@@ -133,10 +145,15 @@ impl SqliteConnectOptions {
             shared_cache: false,
             statement_cache_capacity: 100,
             busy_timeout: Duration::from_secs(5),
+            busy_handler: None,
             log_settings: Default::default(),
             immutable: false,
             pragmas,
             collations: Default::default(),
+            scalar_functions: Default::default(),
+            aggregate_functions: Default::default(),
+            window_functions: Default::default(),
+            extensions: Default::default(),
             serialized: false,
             thread_name: Arc::new(DebugFn(|id| format!("sqlx-sqlite-worker-{}", id))),
             command_channel_size: 50,
@@ -229,8 +246,36 @@ impl SqliteConnectOptions {
     /// returning a busy timeout error.
     ///
     /// The default busy timeout is 5 seconds.
+    ///
+    /// This shares a single slot in SQLite with [`busy_handler`][Self::busy_handler]
+    /// (`sqlite3_busy_timeout` and `sqlite3_busy_handler` both just set the connection's busy
+    /// callback), so calling this clears any handler set previously; whichever of the two is
+    /// called last wins.
     pub fn busy_timeout(mut self, timeout: Duration) -> Self {
         self.busy_timeout = timeout;
+        self.busy_handler = None;
+        self
+    }
+
+    /// Sets a custom callback to invoke when SQLite would otherwise return
+    /// [`SQLITE_BUSY`](https://www.sqlite.org/rescode.html#busy), as an alternative to the
+    /// fixed linear wait of [`busy_timeout`][Self::busy_timeout].
+    ///
+    /// The callback receives the number of times it's already been invoked for the current
+    /// lock (starting at `0`) and returns whether SQLite should keep retrying. This allows
+    /// policies like exponential backoff, jitter, logging, or a hard attempt cap that a flat
+    /// timeout can't express.
+    ///
+    /// Mutually exclusive with [`busy_timeout`][Self::busy_timeout]: both configure the same
+    /// underlying SQLite callback slot. Unlike `busy_timeout`, this doesn't reset the other
+    /// setting's value (there's no "unset" `Duration` to reset it to) — instead, whichever of
+    /// `busy_handler`/`busy_timeout` is connected with wins: if a handler is set, it's installed
+    /// and `busy_timeout`'s value is ignored.
+    pub fn busy_handler<F>(mut self, handler: F) -> Self
+    where
+        F: Fn(u32) -> bool + Send + Sync + 'static,
+    {
+        self.busy_handler = Some(BusyHandler::new(handler));
         self
     }
 
@@ -298,6 +343,111 @@ impl SqliteConnectOptions {
         self
     }
 
+    /// Add a deterministic, custom scalar SQL function callable as `name(...)` in queries.
+    ///
+    /// `num_args` is the number of arguments the function accepts; pass `-1` to accept any
+    /// number of arguments. The function is registered with `SQLITE_DETERMINISTIC`, so SQLite
+    /// may cache and fold calls with constant arguments; only register functions whose result
+    /// depends solely on its arguments.
+    ///
+    /// If a function with the same name and arity already exists, it will be replaced.
+    ///
+    /// See [`sqlite3_create_function_v2()`](https://www.sqlite.org/c3ref/create_function.html)
+    /// for details.
+    pub fn function<N, F, R>(mut self, name: N, num_args: i32, call: F) -> Self
+    where
+        N: Into<Arc<str>>,
+        F: Fn(&[SqliteValue]) -> R + Send + Sync + 'static,
+        R: ToSqliteResult + 'static,
+    {
+        self.scalar_functions
+            .push(ScalarFunction::new(name, num_args, call));
+        self
+    }
+
+    /// Add a custom aggregate SQL function, usable anywhere a built-in aggregate like `SUM`
+    /// or `GROUP_CONCAT` would be.
+    ///
+    /// `init` produces the per-group accumulator state, `step` folds one row's arguments into
+    /// it, and `finish` turns the final state into the function's result.
+    pub fn aggregate_function<S, S0, Step, Finish, R>(
+        mut self,
+        name: impl Into<Arc<str>>,
+        num_args: i32,
+        init: S0,
+        step: Step,
+        finish: Finish,
+    ) -> Self
+    where
+        S: Send + 'static,
+        S0: Fn() -> S + Send + Sync + 'static,
+        Step: Fn(&mut S, &[SqliteValue]) + Send + Sync + 'static,
+        Finish: Fn(S) -> R + Send + Sync + 'static,
+        R: ToSqliteResult + 'static,
+    {
+        self.aggregate_functions
+            .push(AggregateFunction::new(name, num_args, init, step, finish));
+        self
+    }
+
+    /// Add a custom SQL window function, usable with an `OVER (...)` clause.
+    ///
+    /// Like [`aggregate_function`][Self::aggregate_function], but additionally takes `inverse`
+    /// (to remove a row that has slid out of the frame) and `value` (to report the current
+    /// aggregate without consuming it), which SQLite needs to support sliding frames
+    /// efficiently. The accumulator state must be `Clone`.
+    pub fn window_function<S, S0, Step, Inverse, Value, Finish, R>(
+        mut self,
+        name: impl Into<Arc<str>>,
+        num_args: i32,
+        init: S0,
+        step: Step,
+        inverse: Inverse,
+        value: Value,
+        finish: Finish,
+    ) -> Self
+    where
+        S: Clone + Send + 'static,
+        S0: Fn() -> S + Send + Sync + 'static,
+        Step: Fn(&mut S, &[SqliteValue]) + Send + Sync + 'static,
+        Inverse: Fn(&mut S, &[SqliteValue]) + Send + Sync + 'static,
+        Value: Fn(&S) -> R + Send + Sync + 'static,
+        Finish: Fn(S) -> R + Send + Sync + 'static,
+        R: ToSqliteResult + 'static,
+    {
+        self.window_functions.push(WindowFunction::new(
+            name, num_args, init, step, inverse, value, finish,
+        ));
+        self
+    }
+
+    /// Request that a SQLite [loadable extension](https://www.sqlite.org/loadext.html) be
+    /// loaded when the connection is opened, using its default entrypoint.
+    ///
+    /// `name` is resolved the same way as the `load_extension` SQL function: a bare name like
+    /// `"mod_spatialite"` is searched for using the platform's shared library naming
+    /// convention, or a path can be given directly.
+    ///
+    /// Loading is done by briefly enabling `SQLITE_DBCONFIG_ENABLE_LOAD_EXTENSION` for the new
+    /// connection and disabling it again immediately afterwards, so extension loading is never
+    /// left enabled for arbitrary SQL to trigger.
+    pub fn extension(mut self, name: impl Into<String>) -> Self {
+        self.extensions.push(Extension::new(name));
+        self
+    }
+
+    /// Like [`extension`][Self::extension], but with an explicit entrypoint symbol name,
+    /// for extensions that don't use the standard `sqlite3_<name>_init` convention.
+    pub fn extension_with_entrypoint(
+        mut self,
+        name: impl Into<String>,
+        entrypoint: impl Into<String>,
+    ) -> Self {
+        self.extensions
+            .push(Extension::with_entrypoint(name, entrypoint));
+        self
+    }
+
     /// Set to `true` to signal to SQLite that the database file is on read-only media.
     ///
     /// If enabled, SQLite assumes the database file _cannot_ be modified, even by higher
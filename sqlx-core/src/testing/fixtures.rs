@@ -13,11 +13,31 @@ pub struct FixtureSnapshot {
 #[error("could not create {0}")]
 pub struct FixtureError(String);
 
+impl FixtureError {
+    pub(crate) fn new(msg: impl Into<String>) -> Self {
+        FixtureError(msg.into())
+    }
+}
+
 pub struct Fixture {
     ops: Vec<FixtureOp>,
 }
 
-enum FixtureOp {
+impl Fixture {
+    /// Build a fixture directly from a list of operations.
+    ///
+    /// Used by backends that can derive `FixtureOp`s from a native changeset instead of
+    /// structurally diffing two [`FixtureSnapshot`]s (see [`Table::assert_structure`]).
+    pub(crate) fn from_ops(ops: Vec<FixtureOp>) -> Self {
+        Self { ops }
+    }
+
+    pub(crate) fn ops(&self) -> &[FixtureOp] {
+        &self.ops
+    }
+}
+
+pub(crate) enum FixtureOp {
     Truncate(TableName),
     Insert {
         table: TableName,
@@ -35,13 +55,13 @@ enum FixtureOp {
     },
 }
 
-type TableName = Arc<str>;
-type ColumnName = Arc<str>;
-type Value = String;
+pub(crate) type TableName = Arc<str>;
+pub(crate) type ColumnName = Arc<str>;
+pub(crate) type Value = String;
 
 struct Table {
     columns: IndexSet<ColumnName>,
-    rows: Vec<Value>,
+    rows: Vec<IndexMap<ColumnName, Value>>,
     primary_key: Option<ColumnName>,
     foreign_keys: HashMap<ColumnName, (TableName, ColumnName)>,
 }
@@ -74,6 +94,78 @@ impl Table {
             self.foreign_keys,
             other.foreign_keys
         );
+
+        Ok(())
+    }
+
+    /// Diff this table's rows against `previous`, assuming both already passed
+    /// [`assert_structure`][Self::assert_structure].
+    ///
+    /// Matching rows across snapshots needs a stable identity, so tables without a primary key
+    /// can't be diffed row-by-row; they're replayed wholesale instead (`TRUNCATE` followed by
+    /// re-`INSERT`ing every row).
+    fn diff_rows(&self, name: &TableName, previous: &Table) -> Vec<FixtureOp> {
+        let Some(pk) = &self.primary_key else {
+            let mut ops = vec![FixtureOp::Truncate(name.clone())];
+            ops.extend(self.rows.iter().map(|row| self.insert_op(name, row)));
+            return ops;
+        };
+
+        let mut previous_by_pk: IndexMap<Value, &IndexMap<ColumnName, Value>> = previous
+            .rows
+            .iter()
+            .map(|row| (row[pk].clone(), row))
+            .collect();
+
+        let mut ops = Vec::new();
+
+        for row in &self.rows {
+            match previous_by_pk.swap_remove(&row[pk]) {
+                None => ops.push(self.insert_op(name, row)),
+                Some(previous_row) => {
+                    let set: IndexMap<ColumnName, Value> = self
+                        .columns
+                        .iter()
+                        .filter(|column| previous_row[*column] != row[*column])
+                        .map(|column| (column.clone(), row[column].clone()))
+                        .collect();
+
+                    if !set.is_empty() {
+                        let mut cond = IndexMap::new();
+                        cond.insert(pk.clone(), row[pk].clone());
+                        ops.push(FixtureOp::Update {
+                            table: name.clone(),
+                            set,
+                            cond,
+                        });
+                    }
+                }
+            }
+        }
+
+        // Anything left in `previous_by_pk` had a row under that key before and doesn't anymore.
+        for key in previous_by_pk.into_keys() {
+            let mut cond = IndexMap::new();
+            cond.insert(pk.clone(), key);
+            ops.push(FixtureOp::Delete {
+                table: name.clone(),
+                cond,
+            });
+        }
+
+        ops
+    }
+
+    fn insert_op(&self, name: &TableName, row: &IndexMap<ColumnName, Value>) -> FixtureOp {
+        FixtureOp::Insert {
+            table: name.clone(),
+            columns: self.columns.iter().cloned().collect(),
+            rows: self
+                .columns
+                .iter()
+                .map(|column| row[column].clone())
+                .collect(),
+        }
     }
 }
 
@@ -86,4 +178,38 @@ impl FixtureSnapshot {
             previous.tables.keys()
         );
     }
+
+    /// Like [`fixture`][Self::fixture], but only compares the given tables against `previous`,
+    /// trusting the caller that every other table is unchanged.
+    ///
+    /// Intended for callers that tracked dirtied tables via an `update_hook` (see
+    /// [`crate::sqlite::connection::hooks`]) between taking the two snapshots, so they don't pay
+    /// for a full structural diff of tables they already know weren't touched.
+    pub(crate) fn fixture_dirty(
+        &self,
+        previous: &FixtureSnapshot,
+        dirty_tables: &IndexSet<TableName>,
+    ) -> Result<Fixture> {
+        fixture_assert!(
+            self.tables.keys().eq(previous.tables.keys()),
+            "mismatch in tables: {:?} vs {:?}",
+            self.tables.keys(),
+            previous.tables.keys()
+        );
+
+        let mut ops = Vec::new();
+
+        for name in self.tables.keys() {
+            if !dirty_tables.contains(name) {
+                continue;
+            }
+
+            let table = &self.tables[name];
+            let previous_table = &previous.tables[name];
+            table.assert_structure(previous_table)?;
+            ops.extend(table.diff_rows(name, previous_table));
+        }
+
+        Ok(Fixture::from_ops(ops))
+    }
 }
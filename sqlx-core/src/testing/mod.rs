@@ -7,7 +7,7 @@ use crate::database::Database;
 use crate::error::Error;
 use crate::pool::Pool;
 
-mod fixtures;
+pub(crate) mod fixtures;
 
 pub use fixtures::FixtureSnapshot;
 